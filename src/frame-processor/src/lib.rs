@@ -1,22 +1,90 @@
 use wasm_bindgen::prelude::*;
 use web_sys::{ImageData, CanvasRenderingContext2d};
 use std::cell::RefCell;
+use std::collections::VecDeque;
 
 thread_local! {
     static FRAME_BUFFER_POOL: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
+    // Scratch rows for `box_blur_pass`'s sliding window. Kept separate from
+    // FRAME_BUFFER_POOL because these only need `width` bytes each, not a
+    // full frame -- pulling them from the frame pool would drain its 3 slots
+    // after a couple of rows and force fresh frame-sized allocations.
+    static ROW_BUFFER_POOL: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
 }
 
 const MAX_POOLED_BUFFERS: usize = 3;
 const MIN_BUFFER_SIZE: usize = 1024 * 1024; // 1MB
 
+// Block-matching motion estimation tuning. Blocks are fixed 16x16 luma
+// regions (matching the rav1e/nihav convention) and SAD is normalized to a
+// 128x128 reference block so confidence reads the same regardless of the
+// actual block size.
+const BLOCK_SIZE: u32 = 16;
+const MAX_SEARCH_RANGE: i32 = 16;
+const REFERENCE_BLOCK_AREA: f64 = 128.0 * 128.0;
+
+const HEX_OFFSETS: [(i32, i32); 6] = [(-2, 0), (-1, -2), (1, -2), (2, 0), (1, 2), (-1, 2)];
+const SMALL_DIAMOND_OFFSETS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+// Repeated box-filter passes used to approximate a Gaussian blur cheaply.
+const BLUR_PASSES: u32 = 3;
+
+// Temporal lookahead: buffer N frames and only emit a shot once its
+// bright/motion region has persisted across at least K of them, so a
+// single-frame flash (glint, auto-exposure pop) never confirms a shot.
+const LOOKAHEAD_FRAMES: usize = 5;
+const PERSISTENCE_MIN_FRAMES: usize = 3;
+
+// Radius of the local neighborhood a candidate bright spot is compared
+// against in `persists_across_window`, to tell a real lit region (which
+// bleeds into nearby pixels) apart from a one-pixel sensor spike.
+const NEIGHBOR_RADIUS: i32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SearchMode {
+    Diamond,
+    Hexagon,
+    Umh,
+}
+
+/// Per-block motion estimate: displacement in pixels plus the SAD of the
+/// winning match, normalized to a 128x128 reference block so it can be
+/// compared across resolutions.
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct MotionVector {
+    dx: f64,
+    dy: f64,
+    normalized_sad: f64,
+}
+
+/// The active region of interest, in normalized (0..1) frame coordinates,
+/// echoed back in `ShotResult` so the JS side can draw the masked zone.
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct RoiBounds {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
 #[wasm_bindgen]
 pub struct FrameProcessor {
     width: u32,
     height: u32,
     threshold: f64,
-    previous_frame: Option<Vec<u8>>,
+    previous_luma: Option<Vec<u8>>,
     motion_threshold: f64,
     frame_size: usize,
+    search_mode: SearchMode,
+    blur_radius: u32,
+    // Buffered (blurred) luma planes awaiting enough lookahead context to
+    // resolve. Motion search and brightness analysis both read this single
+    // plane instead of each re-deriving their own from the RGBA frame.
+    lookahead: VecDeque<Vec<u8>>,
+    // Pooled byte-per-pixel mask (1 = inside the region of interest). `None`
+    // means the whole frame is in play.
+    roi_mask: Option<Vec<u8>>,
+    roi_bounds: Option<RoiBounds>,
 }
 
 #[wasm_bindgen]
@@ -24,7 +92,7 @@ impl FrameProcessor {
     #[wasm_bindgen(constructor)]
     pub fn new(width: u32, height: u32) -> FrameProcessor {
         let frame_size = (width * height * 4) as usize;
-        
+
         // Pre-allocate buffers
         FRAME_BUFFER_POOL.with(|pool| {
             let mut pool = pool.borrow_mut();
@@ -37,12 +105,138 @@ impl FrameProcessor {
             width,
             height,
             threshold: 0.15,
-            previous_frame: None,
+            previous_luma: None,
             motion_threshold: 0.1,
             frame_size,
+            search_mode: SearchMode::Diamond,
+            blur_radius: 0,
+            lookahead: VecDeque::with_capacity(LOOKAHEAD_FRAMES),
+            roi_mask: None,
+            roi_bounds: None,
         }
     }
 
+    /// Sets the box-blur kernel radius applied to each frame's luma before
+    /// motion detection, to suppress sensor noise that would otherwise
+    /// exceed `motion_threshold`. `0` disables blurring.
+    ///
+    /// Pre-grows `ROW_BUFFER_POOL` to the `2r+1` rows the sliding window
+    /// needs so the blur pass never has to allocate a fresh row mid-frame.
+    #[wasm_bindgen]
+    pub fn set_blur_radius(&mut self, r: u32) {
+        self.blur_radius = r;
+
+        let width = self.width as usize;
+        let needed_rows = (2 * r + 1) as usize;
+        ROW_BUFFER_POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            while pool.len() < needed_rows {
+                pool.push(vec![0; width]);
+            }
+        });
+    }
+
+    /// Restricts detection to a rectangular region of interest (e.g. the
+    /// table felt), in pixel coordinates. Builds a pooled bitmask that
+    /// `detect_motion`, the blur pass and `analyze_brightness` all consult
+    /// to skip pixels outside it.
+    #[wasm_bindgen]
+    pub fn set_roi(&mut self, x: u32, y: u32, w: u32, h: u32) {
+        let mut mask = self.get_buffer_from_pool();
+        mask.resize((self.width * self.height) as usize, 0);
+
+        let start_x = x.min(self.width);
+        let start_y = y.min(self.height);
+        let end_x = (x + w).min(self.width);
+        let end_y = (y + h).min(self.height);
+
+        for row in start_y..end_y {
+            let base = (row * self.width) as usize;
+            for v in &mut mask[base + start_x as usize..base + end_x as usize] {
+                *v = 1;
+            }
+        }
+
+        self.roi_bounds = Some(RoiBounds {
+            x: start_x as f64 / self.width as f64,
+            y: start_y as f64 / self.height as f64,
+            w: (end_x - start_x) as f64 / self.width as f64,
+            h: (end_y - start_y) as f64 / self.height as f64,
+        });
+
+        if let Some(old) = self.roi_mask.replace(mask) {
+            self.return_buffer_to_pool(old);
+        }
+    }
+
+    /// Restricts detection to an arbitrary polygon, given as a flat
+    /// `[x0, y0, x1, y1, ...]` list of pixel-coordinate vertices. Builds the
+    /// same kind of pooled bitmask as `set_roi`, via an even-odd
+    /// point-in-polygon test.
+    #[wasm_bindgen]
+    pub fn set_roi_polygon(&mut self, vertices: &[f64]) {
+        if vertices.len() % 2 != 0 {
+            return;
+        }
+
+        let points: Vec<(f64, f64)> = vertices.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+        if points.len() < 3 {
+            return;
+        }
+
+        let mut mask = self.get_buffer_from_pool();
+        mask.resize((self.width * self.height) as usize, 0);
+
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+        for &(px, py) in &points {
+            min_x = min_x.min(px);
+            min_y = min_y.min(py);
+            max_x = max_x.max(px);
+            max_y = max_y.max(py);
+        }
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if point_in_polygon(x as f64 + 0.5, y as f64 + 0.5, &points) {
+                    mask[(y * self.width + x) as usize] = 1;
+                }
+            }
+        }
+
+        let (frame_w, frame_h) = (self.width as f64, self.height as f64);
+        let (x0, x1) = (min_x.clamp(0.0, frame_w), max_x.clamp(0.0, frame_w));
+        let (y0, y1) = (min_y.clamp(0.0, frame_h), max_y.clamp(0.0, frame_h));
+        self.roi_bounds = Some(RoiBounds {
+            x: x0 / frame_w,
+            y: y0 / frame_h,
+            w: (x1 - x0) / frame_w,
+            h: (y1 - y0) / frame_h,
+        });
+
+        if let Some(old) = self.roi_mask.replace(mask) {
+            self.return_buffer_to_pool(old);
+        }
+    }
+
+    fn in_roi(&self, x: u32, y: u32) -> bool {
+        match &self.roi_mask {
+            Some(mask) => mask[(y * self.width + x) as usize] != 0,
+            None => true,
+        }
+    }
+
+    /// Selects the block-matching search pattern used by motion estimation.
+    /// Accepts `"diamond"`, `"hexagon"` or `"umh"`; unrecognized values fall
+    /// back to diamond search.
+    #[wasm_bindgen]
+    pub fn set_search_mode(&mut self, mode: &str) {
+        self.search_mode = match mode {
+            "hexagon" => SearchMode::Hexagon,
+            "umh" => SearchMode::Umh,
+            _ => SearchMode::Diamond,
+        };
+    }
+
     fn get_buffer_from_pool(&self) -> Vec<u8> {
         FRAME_BUFFER_POOL.with(|pool| {
             let mut pool = pool.borrow_mut();
@@ -62,39 +256,98 @@ impl FrameProcessor {
         }
     }
 
+    /// Pulls a `width`-sized scratch row from `ROW_BUFFER_POOL`, falling
+    /// back to a fresh allocation only if the pool hasn't been pre-grown far
+    /// enough for the current blur radius (e.g. before the first
+    /// `set_blur_radius` call).
+    fn get_row_buffer(&self) -> Vec<u8> {
+        let width = self.width as usize;
+        ROW_BUFFER_POOL.with(|pool| {
+            pool.borrow_mut().pop().unwrap_or_else(|| vec![0; width])
+        })
+    }
+
+    fn return_row_buffer(&self, buffer: Vec<u8>) {
+        ROW_BUFFER_POOL.with(|pool| {
+            pool.borrow_mut().push(buffer);
+        });
+    }
+
+    /// Buffers `frame_data` into the lookahead window. Once the window is
+    /// full this resolves the oldest buffered frame and returns its shot
+    /// result (if any); results are therefore delayed by up to
+    /// `LOOKAHEAD_FRAMES` frames. Call `flush` at stream end to drain the
+    /// frames still sitting in the window.
     #[wasm_bindgen]
     pub fn process_frame(&mut self, frame_data: &[u8]) -> Option<JsValue> {
-        // Get a buffer from the pool instead of creating a new one
         let mut current_frame = self.get_buffer_from_pool();
         current_frame[..frame_data.len()].copy_from_slice(frame_data);
 
+        // Compute the shared luma plane once, then return the RGBA buffer
+        // immediately -- nothing downstream needs it anymore.
+        let current_luma = self.blurred_luma(&current_frame);
+        self.return_buffer_to_pool(current_frame);
+
+        self.lookahead.push_back(current_luma);
+
+        if self.lookahead.len() < LOOKAHEAD_FRAMES {
+            return None;
+        }
+
+        self.resolve_oldest_buffered_frame()
+    }
+
+    /// Drains every frame still sitting in the lookahead window, resolving
+    /// each one against whatever frames remain ahead of it. Call this once
+    /// at stream end so the last few frames aren't silently dropped.
+    #[wasm_bindgen]
+    pub fn flush(&mut self) -> Vec<JsValue> {
+        let mut results = Vec::new();
+        while !self.lookahead.is_empty() {
+            if let Some(result) = self.resolve_oldest_buffered_frame() {
+                results.push(result);
+            }
+        }
+        results
+    }
+
+    /// Pops the oldest buffered frame, diffs it against the previously
+    /// resolved frame to detect motion and a bright spot, then confirms the
+    /// bright spot persists across `PERSISTENCE_MIN_FRAMES` of the window
+    /// before reporting it as a shot. This rejects single-frame flashes
+    /// (sensor glints, auto-exposure pops) that would otherwise read as
+    /// motion.
+    fn resolve_oldest_buffered_frame(&mut self) -> Option<JsValue> {
+        let candidate = self.lookahead.pop_front().unwrap();
+
         let mut shot_detected = false;
         let mut shot_position = (0.0, 0.0);
         let mut confidence = 0.0;
 
-        // Motion detection
-        if let Some(ref prev_frame) = self.previous_frame {
-            let (motion_detected, motion_center, motion_magnitude) = 
-                self.detect_motion(&current_frame, prev_frame);
+        let mut shot_motion = MotionVector { dx: 0.0, dy: 0.0, normalized_sad: 0.0 };
+
+        if let Some(ref prev_luma) = self.previous_luma {
+            let (motion_detected, motion_center, motion_magnitude, motion_vector) =
+                self.detect_motion(&candidate, prev_luma);
 
             if motion_detected {
-                // Brightness analysis in motion area
-                let (bright_spot, brightness) = 
-                    self.analyze_brightness(&current_frame, motion_center);
+                let (bright_spot, brightness) = self.analyze_brightness(&candidate, motion_center);
 
-                if brightness > self.threshold {
+                if brightness > self.threshold
+                    && self.persists_across_window(&candidate, bright_spot, motion_vector)
+                {
                     shot_detected = true;
                     shot_position = bright_spot;
                     confidence = (brightness + motion_magnitude) / 2.0;
+                    shot_motion = motion_vector;
                 }
             }
         }
 
-        // Update previous frame using buffer pooling
-        if let Some(old_frame) = self.previous_frame.take() {
-            self.return_buffer_to_pool(old_frame);
+        if let Some(old_luma) = self.previous_luma.take() {
+            self.return_buffer_to_pool(old_luma);
         }
-        self.previous_frame = Some(current_frame);
+        self.previous_luma = Some(candidate);
 
         if shot_detected {
             let result = JsValue::from_serde(&ShotResult {
@@ -104,6 +357,8 @@ impl FrameProcessor {
                     y: shot_position.1,
                 },
                 confidence,
+                roi: self.roi_bounds,
+                motion: shot_motion,
             }).unwrap();
             Some(result)
         } else {
@@ -111,51 +366,514 @@ impl FrameProcessor {
         }
     }
 
-    fn detect_motion(&self, current: &[u8], previous: &[u8]) -> (bool, (f64, f64), f64) {
+    /// Confirms the candidate bright spot is a real, moving shot rather than
+    /// a single-frame flash (sensor glint, auto-exposure pop), by checking
+    /// two things across the remaining buffered frames:
+    ///
+    /// - The spot's actual luma value, not just the window's max, must beat
+    ///   its own local neighborhood average -- a flash is an isolated spike
+    ///   against its surroundings, real reflected light bleeds into the
+    ///   pixels around it too.
+    /// - The frame-to-frame motion vector must keep pointing roughly the
+    ///   same way the originally detected `motion` did, rather than
+    ///   jittering -- a real shot keeps travelling, a flash doesn't move.
+    ///
+    /// Both checks must hold in at least `PERSISTENCE_MIN_FRAMES` of the
+    /// `LOOKAHEAD_FRAMES` window (counting the candidate itself) for the
+    /// spot to be reported as a shot.
+    fn persists_across_window(&self, candidate: &[u8], bright_spot: (f64, f64), motion: MotionVector) -> bool {
+        let mut persistent_count = 1; // the candidate frame already matched on brightness
+        let mut consistent_count = 1; // the candidate's own motion trivially agrees with itself
+
+        let mut prior: &[u8] = candidate;
+
+        for luma in &self.lookahead {
+            let value = self.spot_luma(luma, bright_spot);
+            let neighbor_avg = self.neighbor_average(luma, bright_spot);
+            if value as f64 / 255.0 > self.threshold && (value as f64) > neighbor_avg {
+                persistent_count += 1;
+            }
+
+            let (_, _, _, mv) = self.detect_motion(luma, prior);
+            if mv.dx * motion.dx + mv.dy * motion.dy > 0.0 {
+                consistent_count += 1;
+            }
+            prior = luma.as_slice();
+        }
+
+        persistent_count >= PERSISTENCE_MIN_FRAMES && consistent_count >= PERSISTENCE_MIN_FRAMES
+    }
+
+    /// Raw luma sample at `spot` (normalized 0..1 coordinates).
+    fn spot_luma(&self, luma: &[u8], spot: (f64, f64)) -> u8 {
+        let x = ((spot.0 * self.width as f64) as u32).min(self.width - 1);
+        let y = ((spot.1 * self.height as f64) as u32).min(self.height - 1);
+        luma[(y * self.width + x) as usize]
+    }
+
+    /// Average luma of the pixels surrounding `spot` (excluding the center
+    /// pixel itself), used as the local "is this actually brighter than its
+    /// neighborhood" baseline for `persists_across_window`.
+    fn neighbor_average(&self, luma: &[u8], spot: (f64, f64)) -> f64 {
+        let cx = ((spot.0 * self.width as f64) as i32).clamp(0, self.width as i32 - 1);
+        let cy = ((spot.1 * self.height as f64) as i32).clamp(0, self.height as i32 - 1);
+
+        let mut sum = 0u32;
+        let mut count = 0u32;
+        for oy in -NEIGHBOR_RADIUS..=NEIGHBOR_RADIUS {
+            for ox in -NEIGHBOR_RADIUS..=NEIGHBOR_RADIUS {
+                if ox == 0 && oy == 0 {
+                    continue;
+                }
+                let x = cx + ox;
+                let y = cy + oy;
+                if x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32 {
+                    continue;
+                }
+                sum += luma[(y as u32 * self.width + x as u32) as usize] as u32;
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            0.0
+        } else {
+            sum as f64 / count as f64
+        }
+    }
+
+    /// Runs block-matching motion estimation over a pair of (already
+    /// blurred) luma planes -- the same plane `analyze_brightness` reads --
+    /// and aggregates the result into the same `(detected, center,
+    /// magnitude)` shape the brightness stage expects, alongside the
+    /// dominant `MotionVector` so callers can reconstruct shot direction
+    /// and speed.
+    fn detect_motion(&self, current: &[u8], previous: &[u8]) -> (bool, (f64, f64), f64, MotionVector) {
+        let blocks_x = self.width / BLOCK_SIZE;
+        let blocks_y = self.height / BLOCK_SIZE;
+
+        // Raster-order grid of winning MVs, used as neighbor predictors for
+        // UMH's predicted starting point.
+        let mut mv_grid: Vec<Option<(i32, i32)>> = vec![None; (blocks_x * blocks_y) as usize];
+
         let mut diff_sum = 0.0;
         let mut motion_x = 0.0;
         let mut motion_y = 0.0;
-        let mut pixel_count = 0.0;
-
-        // Process pixels in chunks for better cache utilization
-        for chunk in 0..(self.width * self.height) as usize / 64 {
-            let start = chunk * 64 * 4;
-            let end = start + 64 * 4;
-            
-            for idx in (start..end).step_by(4) {
-                // Calculate pixel difference
-                let diff_r = (current[idx] as f64 - previous[idx] as f64).abs();
-                let diff_g = (current[idx + 1] as f64 - previous[idx + 1] as f64).abs();
-                let diff_b = (current[idx + 2] as f64 - previous[idx + 2] as f64).abs();
-                
-                let diff = (diff_r + diff_g + diff_b) / (3.0 * 255.0);
-                
-                if diff > self.motion_threshold {
-                    diff_sum += diff;
-                    let x = ((idx / 4) % self.width as usize) as f64;
-                    let y = ((idx / 4) / self.width as usize) as f64;
-                    motion_x += x * diff;
-                    motion_y += y * diff;
-                    pixel_count += 1.0;
-                }
-            }
-        }
-
-        if pixel_count > 0.0 {
-            let magnitude = diff_sum / pixel_count;
+        let mut vec_dx_sum = 0.0;
+        let mut vec_dy_sum = 0.0;
+        let mut sad_sum = 0.0;
+        let mut moving_blocks = 0.0;
+
+        for by in 0..blocks_y {
+            for bx in 0..blocks_x {
+                let px = (bx * BLOCK_SIZE) as i32;
+                let py = (by * BLOCK_SIZE) as i32;
+
+                let roi_pixels = self.block_roi_count(px, py);
+                if roi_pixels == 0 {
+                    // Block falls entirely outside the region of interest.
+                    continue;
+                }
+
+                let (mv, sad) = match self.search_mode {
+                    SearchMode::Diamond => self.search_diamond(current, previous, px, py, (0, 0)),
+                    SearchMode::Hexagon => self.search_hexagon(current, previous, px, py, (0, 0)),
+                    SearchMode::Umh => {
+                        let predicted = self.predicted_motion_vector(&mv_grid, bx, by, blocks_x);
+                        self.search_umh(current, previous, px, py, predicted)
+                    }
+                };
+
+                mv_grid[(by * blocks_x + bx) as usize] = Some(mv);
+
+                let avg_diff = (sad / roi_pixels as f64 / 255.0).min(1.0);
+
+                if avg_diff > self.motion_threshold {
+                    moving_blocks += 1.0;
+                    diff_sum += avg_diff;
+
+                    let center_x = px as f64 + BLOCK_SIZE as f64 / 2.0;
+                    let center_y = py as f64 + BLOCK_SIZE as f64 / 2.0;
+                    motion_x += center_x * avg_diff;
+                    motion_y += center_y * avg_diff;
+                    vec_dx_sum += mv.0 as f64 * avg_diff;
+                    vec_dy_sum += mv.1 as f64 * avg_diff;
+                    sad_sum += sad * avg_diff;
+                }
+            }
+        }
+
+        if diff_sum > 0.0 {
+            let magnitude = diff_sum / moving_blocks;
             let center_x = motion_x / (diff_sum * self.width as f64);
             let center_y = motion_y / (diff_sum * self.height as f64);
-            (magnitude > self.motion_threshold, (center_x, center_y), magnitude)
+
+            let avg_sad = sad_sum / diff_sum;
+            let block_area = (BLOCK_SIZE * BLOCK_SIZE) as f64;
+            let motion_vector = MotionVector {
+                dx: vec_dx_sum / diff_sum,
+                dy: vec_dy_sum / diff_sum,
+                normalized_sad: avg_sad * (REFERENCE_BLOCK_AREA / block_area),
+            };
+
+            (magnitude > self.motion_threshold, (center_x, center_y), magnitude, motion_vector)
         } else {
-            (false, (0.0, 0.0), 0.0)
+            (false, (0.0, 0.0), 0.0, MotionVector { dx: 0.0, dy: 0.0, normalized_sad: 0.0 })
+        }
+    }
+
+    /// Number of pixels of the block at `(bx, by)` that fall inside the
+    /// region of interest (the full block when no ROI is set). Used both to
+    /// skip blocks that fall entirely outside it and to normalize their SAD.
+    fn block_roi_count(&self, bx: i32, by: i32) -> u32 {
+        if self.roi_mask.is_none() {
+            return BLOCK_SIZE * BLOCK_SIZE;
+        }
+
+        let mut count = 0;
+        for row in 0..BLOCK_SIZE as i32 {
+            let y = by + row;
+            if y < 0 || y >= self.height as i32 {
+                continue;
+            }
+            for col in 0..BLOCK_SIZE as i32 {
+                let x = bx + col;
+                if x < 0 || x >= self.width as i32 {
+                    continue;
+                }
+                if self.in_roi(x as u32, y as u32) {
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Sum of absolute luma differences between the block at `(bx, by)` in
+    /// `current` and the block displaced by `(dx, dy)` in `previous`.
+    /// `current`/`previous` are single-channel luma planes. Displacements
+    /// that would read outside the frame are rejected with `f64::MAX` so
+    /// the search patterns never pick them; pixels outside the region of
+    /// interest are skipped rather than counted.
+    fn block_sad(&self, current: &[u8], previous: &[u8], bx: i32, by: i32, dx: i32, dy: i32) -> f64 {
+        let mut sad = 0.0;
+
+        for row in 0..BLOCK_SIZE as i32 {
+            let cy = by + row;
+            let py = cy + dy;
+            if cy < 0 || cy >= self.height as i32 || py < 0 || py >= self.height as i32 {
+                return f64::MAX;
+            }
+
+            for col in 0..BLOCK_SIZE as i32 {
+                let cx = bx + col;
+                let px = cx + dx;
+                if cx < 0 || cx >= self.width as i32 || px < 0 || px >= self.width as i32 {
+                    return f64::MAX;
+                }
+
+                if !self.in_roi(cx as u32, cy as u32) {
+                    continue;
+                }
+
+                let c_idx = (cy as u32 * self.width + cx as u32) as usize;
+                let p_idx = (py as u32 * self.width + px as u32) as usize;
+
+                sad += (current[c_idx] as f64 - previous[p_idx] as f64).abs();
+            }
+        }
+
+        sad
+    }
+
+    /// Diamond search: repeatedly test the 4 neighbors at step `s` around
+    /// the current best match, recentering on the winner. When the center
+    /// wins, halve `s`; stop once `s` reaches 1 and the center still wins.
+    fn search_diamond(&self, current: &[u8], previous: &[u8], bx: i32, by: i32, start: (i32, i32)) -> ((i32, i32), f64) {
+        let mut best = start;
+        let mut best_sad = self.block_sad(current, previous, bx, by, best.0, best.1);
+        let mut step = MAX_SEARCH_RANGE / 2;
+
+        while step >= 1 {
+            let candidates = [
+                (best.0 + step, best.1),
+                (best.0 - step, best.1),
+                (best.0, best.1 + step),
+                (best.0, best.1 - step),
+            ];
+
+            let mut center_wins = true;
+            for &(dx, dy) in &candidates {
+                let sad = self.block_sad(current, previous, bx, by, dx, dy);
+                if sad < best_sad {
+                    best_sad = sad;
+                    best = (dx, dy);
+                    center_wins = false;
+                }
+            }
+
+            if center_wins {
+                if step == 1 {
+                    break;
+                }
+                step /= 2;
+            }
+        }
+
+        (best, best_sad)
+    }
+
+    /// Hexagon search: repeatedly test the 6 points of a fixed-radius
+    /// hexagon around the current best match, recentering on the winner
+    /// until the center wins, then apply a small diamond for final
+    /// refinement.
+    fn search_hexagon(&self, current: &[u8], previous: &[u8], bx: i32, by: i32, start: (i32, i32)) -> ((i32, i32), f64) {
+        let start_sad = self.block_sad(current, previous, bx, by, start.0, start.1);
+        self.hexagon_refine(current, previous, bx, by, start, start_sad)
+    }
+
+    fn hexagon_refine(&self, current: &[u8], previous: &[u8], bx: i32, by: i32, start: (i32, i32), start_sad: f64) -> ((i32, i32), f64) {
+        let mut best = start;
+        let mut best_sad = start_sad;
+
+        loop {
+            let mut moved = false;
+            for &(ox, oy) in &HEX_OFFSETS {
+                let (dx, dy) = (best.0 + ox, best.1 + oy);
+                let sad = self.block_sad(current, previous, bx, by, dx, dy);
+                if sad < best_sad {
+                    best_sad = sad;
+                    best = (dx, dy);
+                    moved = true;
+                }
+            }
+            if !moved {
+                break;
+            }
+        }
+
+        for &(ox, oy) in &SMALL_DIAMOND_OFFSETS {
+            let (dx, dy) = (best.0 + ox, best.1 + oy);
+            let sad = self.block_sad(current, previous, bx, by, dx, dy);
+            if sad < best_sad {
+                best_sad = sad;
+                best = (dx, dy);
+            }
+        }
+
+        (best, best_sad)
+    }
+
+    /// Uneven Multi-Hexagon search: starts from the predicted MV (median of
+    /// the left/top/top-right neighbor blocks' MVs and the zero MV), does an
+    /// initial cross search at a large radius, a raster scan over a small
+    /// window, then refines with the same iterative hexagon shrink as
+    /// `search_hexagon`.
+    fn search_umh(&self, current: &[u8], previous: &[u8], bx: i32, by: i32, predicted: (i32, i32)) -> ((i32, i32), f64) {
+        let mut best = predicted;
+        let mut best_sad = self.block_sad(current, previous, bx, by, best.0, best.1);
+
+        let cross_offsets = [
+            (MAX_SEARCH_RANGE, 0),
+            (-MAX_SEARCH_RANGE, 0),
+            (0, MAX_SEARCH_RANGE),
+            (0, -MAX_SEARCH_RANGE),
+        ];
+        for &(ox, oy) in &cross_offsets {
+            let (dx, dy) = (predicted.0 + ox, predicted.1 + oy);
+            let sad = self.block_sad(current, previous, bx, by, dx, dy);
+            if sad < best_sad {
+                best_sad = sad;
+                best = (dx, dy);
+            }
+        }
+
+        let window = 2;
+        for oy in -window..=window {
+            for ox in -window..=window {
+                let (dx, dy) = (best.0 + ox, best.1 + oy);
+                let sad = self.block_sad(current, previous, bx, by, dx, dy);
+                if sad < best_sad {
+                    best_sad = sad;
+                    best = (dx, dy);
+                }
+            }
+        }
+
+        self.hexagon_refine(current, previous, bx, by, best, best_sad)
+    }
+
+    /// Predicted MV for UMH: the component-wise median of the zero MV and
+    /// the already-resolved left, top and top-right neighbor blocks' MVs.
+    fn predicted_motion_vector(&self, mv_grid: &[Option<(i32, i32)>], bx: u32, by: u32, blocks_x: u32) -> (i32, i32) {
+        let mut candidates: Vec<(i32, i32)> = vec![(0, 0)];
+
+        if bx > 0 {
+            if let Some(mv) = mv_grid[(by * blocks_x + bx - 1) as usize] {
+                candidates.push(mv);
+            }
+        }
+        if by > 0 {
+            if let Some(mv) = mv_grid[((by - 1) * blocks_x + bx) as usize] {
+                candidates.push(mv);
+            }
+            if bx + 1 < blocks_x {
+                if let Some(mv) = mv_grid[((by - 1) * blocks_x + bx + 1) as usize] {
+                    candidates.push(mv);
+                }
+            }
+        }
+
+        let mut dxs: Vec<i32> = candidates.iter().map(|c| c.0).collect();
+        let mut dys: Vec<i32> = candidates.iter().map(|c| c.1).collect();
+        dxs.sort_unstable();
+        dys.sort_unstable();
+
+        (dxs[dxs.len() / 2], dys[dys.len() / 2])
+    }
+
+    /// Converts an RGBA frame into a pooled single-channel luma plane, then
+    /// runs it through `BLUR_PASSES` box-filter passes (skipped when
+    /// `blur_radius` is 0) to denoise it before motion search.
+    fn blurred_luma(&self, frame: &[u8]) -> Vec<u8> {
+        let mut luma = self.rgba_to_luma(frame);
+
+        if self.blur_radius == 0 {
+            return luma;
+        }
+
+        for _ in 0..BLUR_PASSES {
+            let blurred = self.box_blur_pass(&luma, self.blur_radius);
+            self.return_buffer_to_pool(luma);
+            luma = blurred;
+        }
+
+        luma
+    }
+
+    fn rgba_to_luma(&self, frame: &[u8]) -> Vec<u8> {
+        let pixel_count = (self.width * self.height) as usize;
+
+        let mut luma = self.get_buffer_from_pool();
+        luma.resize(pixel_count, 0);
+
+        for i in 0..pixel_count {
+            let idx = i * 4;
+            luma[i] = (frame[idx] as f64 * 0.299
+                + frame[idx + 1] as f64 * 0.587
+                + frame[idx + 2] as f64 * 0.114) as u8;
+        }
+
+        luma
+    }
+
+    /// Separable box blur implemented as a sliding window: a FIFO of the
+    /// last `2r+1` source rows feeds a per-column running sum, and each
+    /// output pixel is the horizontal running sum of `2r+1` of those column
+    /// sums divided by `(2r+1)^2`. The row FIFO is pulled from
+    /// `ROW_BUFFER_POOL` (pre-grown by `set_blur_radius`) and the
+    /// column-sum accumulator and output plane from `FRAME_BUFFER_POOL`
+    /// (the accumulator packed as little-endian `u32`s). A pool that is
+    /// empty falls back to a fresh allocation; `FRAME_BUFFER_POOL` holds at
+    /// most `MAX_POOLED_BUFFERS` buffers, which the frame, luma and output
+    /// planes of a frame can use up, so the accumulator of the first pass
+    /// is then a fresh frame-sized allocation.
+    fn box_blur_pass(&self, src: &[u8], radius: u32) -> Vec<u8> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let r = radius as i64;
+        let window_rows = (2 * r + 1) as f64;
+        let norm = window_rows * window_rows;
+
+        let clamp_row = |y: i64| -> usize { y.clamp(0, height as i64 - 1) as usize };
+        let clamp_col = |x: i64| -> usize { x.clamp(0, width as i64 - 1) as usize };
+
+        let mut dst = self.get_buffer_from_pool();
+        dst.resize(width * height, 0);
+
+        let mut col_sums = self.get_buffer_from_pool();
+        col_sums.resize(width * 4, 0);
+
+        let mut row_fifo: VecDeque<Vec<u8>> = VecDeque::with_capacity((2 * r + 1) as usize);
+
+        let pull_row = |src_y: usize| -> Vec<u8> {
+            let mut row = self.get_row_buffer();
+            row.resize(width, 0);
+            row.copy_from_slice(&src[src_y * width..src_y * width + width]);
+            row
+        };
+
+        for i in -r..=r {
+            let row = pull_row(clamp_row(i));
+            for x in 0..width {
+                let sum = Self::read_col_sum(&col_sums, x) + row[x] as u32;
+                Self::write_col_sum(&mut col_sums, x, sum);
+            }
+            row_fifo.push_back(row);
+        }
+
+        for y in 0..height {
+            let mut running: u32 = (-r..=r).map(|ox| Self::read_col_sum(&col_sums, clamp_col(ox))).sum();
+
+            for x in 0..width {
+                // Pixels outside the region of interest are not blurred;
+                // they keep their raw value.
+                dst[y * width + x] = if self.in_roi(x as u32, y as u32) {
+                    (running as f64 / norm) as u8
+                } else {
+                    src[y * width + x]
+                };
+
+                if x + 1 < width {
+                    let leaving = Self::read_col_sum(&col_sums, clamp_col(x as i64 - r));
+                    let entering = Self::read_col_sum(&col_sums, clamp_col(x as i64 + 1 + r));
+                    running = running - leaving + entering;
+                }
+            }
+
+            if y + 1 < height {
+                let old_row = row_fifo.pop_front().unwrap();
+                for x in 0..width {
+                    let sum = Self::read_col_sum(&col_sums, x) - old_row[x] as u32;
+                    Self::write_col_sum(&mut col_sums, x, sum);
+                }
+                self.return_row_buffer(old_row);
+
+                let new_row = pull_row(clamp_row(y as i64 + 1 + r));
+                for x in 0..width {
+                    let sum = Self::read_col_sum(&col_sums, x) + new_row[x] as u32;
+                    Self::write_col_sum(&mut col_sums, x, sum);
+                }
+                row_fifo.push_back(new_row);
+            }
+        }
+
+        while let Some(row) = row_fifo.pop_front() {
+            self.return_row_buffer(row);
         }
+        self.return_buffer_to_pool(col_sums);
+
+        dst
     }
 
-    fn analyze_brightness(&self, frame: &[u8], center: (f64, f64)) -> ((f64, f64), f64) {
+    fn read_col_sum(col_sums: &[u8], x: usize) -> u32 {
+        let i = x * 4;
+        u32::from_le_bytes([col_sums[i], col_sums[i + 1], col_sums[i + 2], col_sums[i + 3]])
+    }
+
+    fn write_col_sum(col_sums: &mut [u8], x: usize, value: u32) {
+        let i = x * 4;
+        col_sums[i..i + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Reads brightness directly from the shared luma plane -- the same
+    /// one `detect_motion` diffed -- instead of re-deriving it from RGBA.
+    fn analyze_brightness(&self, luma: &[u8], center: (f64, f64)) -> ((f64, f64), f64) {
         let window_size = 32; // Analysis window size
         let center_x = (center.0 * self.width as f64) as u32;
         let center_y = (center.1 * self.height as f64) as u32;
-        
+
         let start_x = center_x.saturating_sub(window_size / 2);
         let start_y = center_y.saturating_sub(window_size / 2);
         let end_x = (center_x + window_size / 2).min(self.width);
@@ -166,15 +884,16 @@ impl FrameProcessor {
 
         for y in start_y..end_y {
             for x in start_x..end_x {
-                let idx = ((y * self.width + x) * 4) as usize;
-                let brightness = 
-                    (frame[idx] as f64 * 0.299 + 
-                     frame[idx + 1] as f64 * 0.587 + 
-                     frame[idx + 2] as f64 * 0.114) / 255.0;
+                if !self.in_roi(x, y) {
+                    continue;
+                }
+
+                let idx = (y * self.width + x) as usize;
+                let brightness = luma[idx] as f64 / 255.0;
 
                 if brightness > max_brightness {
                     max_brightness = brightness;
-                    bright_spot = (x as f64 / self.width as f64, 
+                    bright_spot = (x as f64 / self.width as f64,
                                  y as f64 / self.height as f64);
                 }
             }
@@ -185,18 +904,47 @@ impl FrameProcessor {
 
     #[wasm_bindgen]
     pub fn cleanup(&mut self) {
-        // Return the previous frame buffer to the pool if it exists
-        if let Some(frame) = self.previous_frame.take() {
-            self.return_buffer_to_pool(frame);
+        // Return the previous luma plane and any still-buffered lookahead
+        // planes to the pool.
+        if let Some(luma) = self.previous_luma.take() {
+            self.return_buffer_to_pool(luma);
+        }
+        while let Some(luma) = self.lookahead.pop_front() {
+            self.return_buffer_to_pool(luma);
+        }
+        if let Some(mask) = self.roi_mask.take() {
+            self.return_buffer_to_pool(mask);
         }
 
-        // Clear the buffer pool
+        // Clear the buffer pools
         FRAME_BUFFER_POOL.with(|pool| {
             pool.borrow_mut().clear();
         });
+        ROW_BUFFER_POOL.with(|pool| {
+            pool.borrow_mut().clear();
+        });
     }
 }
 
+/// Even-odd ray-casting point-in-polygon test used to rasterize
+/// `set_roi_polygon`'s vertex list into a bitmask.
+fn point_in_polygon(x: f64, y: f64, points: &[(f64, f64)]) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+
+    for i in 0..points.len() {
+        let (xi, yi) = points[i];
+        let (xj, yj) = points[j];
+
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
 #[derive(serde::Serialize)]
 struct Position {
     x: f64,
@@ -208,4 +956,134 @@ struct ShotResult {
     detected: bool,
     position: Position,
     confidence: f64,
-} 
\ No newline at end of file
+    roi: Option<RoiBounds>,
+    motion: MotionVector,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_DIM: u32 = 64;
+
+    /// Builds a pair of luma planes containing a 16x16 square of value 200
+    /// against a zero background, with the square in `previous` offset by
+    /// `(dx0, dy0)` relative to its position in `current`. Since the square
+    /// is exactly one block wide, `(dx0, dy0)` is the unique displacement at
+    /// which the block at `(24, 24)` has zero SAD against `previous`.
+    fn shifted_square_frames(dx0: i32, dy0: i32) -> (Vec<u8>, Vec<u8>) {
+        let width = FRAME_DIM as usize;
+        let height = FRAME_DIM as usize;
+
+        let mut current = vec![0u8; width * height];
+        for y in 24..40 {
+            for x in 24..40 {
+                current[y * width + x] = 200;
+            }
+        }
+
+        let mut previous = vec![0u8; width * height];
+        let px0 = (24 + dx0) as usize;
+        let py0 = (24 + dy0) as usize;
+        for y in py0..py0 + 16 {
+            for x in px0..px0 + 16 {
+                previous[y * width + x] = 200;
+            }
+        }
+
+        (current, previous)
+    }
+
+    #[test]
+    fn block_sad_is_zero_for_identical_blocks() {
+        let fp = FrameProcessor::new(FRAME_DIM, FRAME_DIM);
+        let luma = vec![123u8; (FRAME_DIM * FRAME_DIM) as usize];
+        assert_eq!(fp.block_sad(&luma, &luma, 16, 16, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn block_sad_rejects_out_of_bounds_displacement() {
+        let fp = FrameProcessor::new(FRAME_DIM, FRAME_DIM);
+        let luma = vec![0u8; (FRAME_DIM * FRAME_DIM) as usize];
+        assert_eq!(fp.block_sad(&luma, &luma, 0, 0, -1, 0), f64::MAX);
+    }
+
+    #[test]
+    fn search_diamond_locates_unique_zero_sad_shift() {
+        let fp = FrameProcessor::new(FRAME_DIM, FRAME_DIM);
+        let (current, previous) = shifted_square_frames(3, -2);
+
+        let (mv, sad) = fp.search_diamond(&current, &previous, 24, 24, (0, 0));
+
+        assert_eq!(mv, (3, -2));
+        assert_eq!(sad, 0.0);
+    }
+
+    #[test]
+    fn search_hexagon_locates_unique_zero_sad_shift() {
+        let fp = FrameProcessor::new(FRAME_DIM, FRAME_DIM);
+        let (current, previous) = shifted_square_frames(3, -2);
+
+        let (mv, sad) = fp.search_hexagon(&current, &previous, 24, 24, (0, 0));
+
+        assert_eq!(mv, (3, -2));
+        assert_eq!(sad, 0.0);
+    }
+
+    #[test]
+    fn search_umh_locates_unique_zero_sad_shift() {
+        let fp = FrameProcessor::new(FRAME_DIM, FRAME_DIM);
+        let (current, previous) = shifted_square_frames(3, -2);
+
+        let (mv, sad) = fp.search_umh(&current, &previous, 24, 24, (0, 0));
+
+        assert_eq!(mv, (3, -2));
+        assert_eq!(sad, 0.0);
+    }
+
+    /// Direct (non-sliding-window) box filter with the same edge-clamping
+    /// behavior as `box_blur_pass`, used as a reference to check the
+    /// sliding-window implementation against.
+    fn naive_box_blur(src: &[u8], width: usize, height: usize, radius: i64) -> Vec<u8> {
+        let window = (2 * radius + 1) as f64;
+        let norm = window * window;
+        let clamp_row = |y: i64| -> usize { y.clamp(0, height as i64 - 1) as usize };
+        let clamp_col = |x: i64| -> usize { x.clamp(0, width as i64 - 1) as usize };
+
+        let mut dst = vec![0u8; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0u32;
+                for oy in -radius..=radius {
+                    let sy = clamp_row(y as i64 + oy);
+                    for ox in -radius..=radius {
+                        let sx = clamp_col(x as i64 + ox);
+                        sum += src[sy * width + sx] as u32;
+                    }
+                }
+                dst[y * width + x] = (sum as f64 / norm) as u8;
+            }
+        }
+        dst
+    }
+
+    #[test]
+    fn box_blur_pass_matches_naive_reference() {
+        let width: u32 = 20;
+        let height: u32 = 16;
+        let radius: u32 = 2;
+
+        let mut fp = FrameProcessor::new(width, height);
+        fp.set_blur_radius(radius);
+
+        let mut src = vec![0u8; (width * height) as usize];
+        for (i, v) in src.iter_mut().enumerate() {
+            *v = ((i * 37) % 256) as u8;
+        }
+
+        let actual = fp.box_blur_pass(&src, radius);
+        let expected = naive_box_blur(&src, width as usize, height as usize, radius as i64);
+
+        assert_eq!(actual, expected);
+    }
+}